@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// `message_id(8) + fragment_index(4) + fragment_count(4) + total_len(4)`.
+pub const FRAGMENT_HEADER_LEN: usize = 20;
+
+/// Splits `message` into `mtu`-sized datagrams, each prefixed with a
+/// fragment header so the receiver can reassemble them in any order.
+pub fn fragment_message(message_id: u64, message: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+    let chunk_len = mtu - FRAGMENT_HEADER_LEN;
+    let fragment_count = message.len().max(1).div_ceil(chunk_len) as u32;
+
+    let chunks: Vec<&[u8]> = if message.is_empty() {
+        vec![&message[0..0]]
+    } else {
+        message.chunks(chunk_len).collect()
+    };
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(fragment_index, chunk)| {
+            let mut datagram = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+            datagram.extend_from_slice(&message_id.to_be_bytes());
+            datagram.extend_from_slice(&(fragment_index as u32).to_be_bytes());
+            datagram.extend_from_slice(&fragment_count.to_be_bytes());
+            datagram.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect()
+}
+
+/// Returns `(message_id, fragment_index, fragment_count, total_len)`.
+pub fn read_fragment_header(datagram: &[u8]) -> (u64, u32, u32, u32) {
+    let message_id = u64::from_be_bytes(datagram[0..8].try_into().unwrap());
+    let fragment_index = u32::from_be_bytes(datagram[8..12].try_into().unwrap());
+    let fragment_count = u32::from_be_bytes(datagram[12..16].try_into().unwrap());
+    let total_len = u32::from_be_bytes(datagram[16..20].try_into().unwrap());
+    (message_id, fragment_index, fragment_count, total_len)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompletedMessage {
+    pub message_id: u64,
+    pub total_len: u32,
+    pub fragment_count: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AbortedMessage {
+    pub message_id: u64,
+    pub total_len: u32,
+    pub fragment_count: u32,
+    pub fragments_received: u32,
+}
+
+struct PartialMessage {
+    fragment_count: u32,
+    /// Byte length of each fragment received so far, keyed by fragment
+    /// index. Only the length is kept (not the fragment's bytes) since the
+    /// reassembled content itself isn't needed downstream, just its total
+    /// size once every fragment has arrived.
+    fragment_lens: HashMap<u32, u32>,
+    first_seen: Instant,
+}
+
+/// Reassembles fragmented logical messages per message id, bounding the
+/// memory held for stalled messages by timing out and dropping any that
+/// stay incomplete for longer than `timeout`.
+pub struct Reassembler {
+    partials: HashMap<u64, PartialMessage>,
+    timeout: Duration,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Reassembler {
+        Reassembler {
+            partials: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Feeds one received datagram into the reassembler, returning the
+    /// completed message once every one of its fragments has arrived.
+    pub fn on_fragment(&mut self, datagram: &[u8], now: Instant) -> Option<CompletedMessage> {
+        if datagram.len() < FRAGMENT_HEADER_LEN {
+            return None;
+        }
+
+        let (message_id, fragment_index, fragment_count, _total_len) = read_fragment_header(datagram);
+
+        if fragment_count == 0 || fragment_index >= fragment_count {
+            return None;
+        }
+
+        let fragment_len = (datagram.len() - FRAGMENT_HEADER_LEN) as u32;
+
+        let partial = self.partials.entry(message_id).or_insert_with(|| PartialMessage {
+            fragment_count,
+            fragment_lens: HashMap::new(),
+            first_seen: now,
+        });
+        partial.fragment_lens.insert(fragment_index, fragment_len);
+
+        if partial.fragment_lens.len() as u32 >= partial.fragment_count {
+            let partial = self.partials.remove(&message_id).unwrap();
+            Some(CompletedMessage {
+                message_id,
+                total_len: partial.fragment_lens.values().sum(),
+                fragment_count,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Drops partial messages that have been incomplete for longer than
+    /// `timeout` and reports them as aborted.
+    pub fn expire(&mut self, now: Instant) -> Vec<AbortedMessage> {
+        let timeout = self.timeout;
+        let expired_ids: Vec<u64> = self
+            .partials
+            .iter()
+            .filter(|(_, partial)| now.duration_since(partial.first_seen) > timeout)
+            .map(|(&message_id, _)| message_id)
+            .collect();
+
+        expired_ids
+            .into_iter()
+            .map(|message_id| {
+                let partial = self.partials.remove(&message_id).unwrap();
+                AbortedMessage {
+                    message_id,
+                    total_len: partial.fragment_lens.values().sum(),
+                    fragment_count: partial.fragment_count,
+                    fragments_received: partial.fragment_lens.len() as u32,
+                }
+            })
+            .collect()
+    }
+}
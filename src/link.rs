@@ -1,25 +1,75 @@
-use anyhow::Result;
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use crate::fragment::{self, Reassembler};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc;
 use std::sync::Arc;
-use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum LinkMode {
     Tx,
     Rx,
+    /// Reliable mode: sends its own stream like `Tx` and receives the peer's
+    /// stream like `Rx` over the same socket, NACKing gaps on receive and
+    /// retransmitting NACKed sequence numbers it still has buffered on send.
+    Arq,
+}
+
+/// Size of the header written into the start of every Tx/Rx payload: an 8
+/// byte big-endian sequence number followed by the 8 byte `f64` send
+/// timestamp.
+const HEADER_LEN: usize = 16;
+
+/// How many sequence numbers the Rx reorder/loss detector keeps open before
+/// giving up on a gap and counting it as lost.
+const REORDER_WINDOW_SIZE: usize = 64;
+
+/// Tag byte identifying an Arq wire packet as a data packet vs. a NACK.
+const ARQ_TAG_DATA: u8 = 0;
+const ARQ_TAG_NACK: u8 = 1;
+
+/// `tag(1) + seq(8) + tx_time(8) + retransmit_count(4)`.
+const ARQ_DATA_HEADER_LEN: usize = 21;
+
+/// How many recently sent packets an Arq link keeps around so a NACK can
+/// still be served; older entries are evicted first, ring-buffer style.
+const ARQ_SEND_BUFFER_CAPACITY: usize = 256;
+
+/// How far ahead of the next expected sequence number an Arq receiver will
+/// track out-of-order arrivals before giving up on the gap.
+const ARQ_RECEIVE_WINDOW_SIZE: u64 = 256;
+
+/// Path MTU to fragment logical messages down to by default, chosen to stay
+/// under the typical path MTU once UDP/IP headers are accounted for.
+pub const DEFAULT_FRAGMENT_MTU: i32 = 1472;
+
+/// How long a partially reassembled message is kept around before it's given
+/// up on and reported as aborted.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Configures the Tx/Rx fragmentation layer: Tx splits each logical message
+/// of `message_size` bytes into `mtu`-sized datagrams; Rx reassembles them
+/// per message id.
+#[derive(Debug, Copy, Clone)]
+pub struct FragmentationConfig {
+    pub message_size: i32,
+    pub mtu: i32,
 }
 
 #[derive(Debug)]
 pub struct Link {
     pub run: Arc<AtomicBool>,
-    pub thread: Option<JoinHandle<()>>,
+    pub task: Option<JoinHandle<()>>,
     pub link_mode: LinkMode,
-    pub address: String,
+    pub bind_address: String,
     pub bind_port: u16,
+    pub target_address: String,
     pub target_port: u16,
     pub packet_size: i32,
     pub send_interval_us: i32,
@@ -27,108 +77,741 @@ pub struct Link {
 
 #[derive(Debug)]
 pub struct LinkPacketData {
-    t: f64,
-    payload_size: i32,
+    pub(crate) t: f64,
+    pub(crate) payload_size: i32,
+    pub(crate) seq: u64,
+    pub(crate) latency_s: f64,
+    pub(crate) lost_count: u64,
+    pub(crate) reordered: bool,
+    pub(crate) duplicate: bool,
+    pub(crate) retransmit_count: u32,
+    /// Number of datagram fragments the logical message was split into; `1`
+    /// for packets that weren't fragmented.
+    pub(crate) fragment_count: u32,
+    /// Set when a fragmented message was given up on after some, but not
+    /// all, of its fragments arrived.
+    pub(crate) aborted: bool,
+}
+
+fn write_header(payload: &mut [u8], seq: u64, tx_time_s: f64) {
+    payload[0..8].copy_from_slice(&seq.to_be_bytes());
+    payload[8..16].copy_from_slice(&tx_time_s.to_be_bytes());
+}
+
+fn read_header(payload: &[u8]) -> (u64, f64) {
+    let seq = u64::from_be_bytes(payload[0..8].try_into().unwrap());
+    let tx_time_s = f64::from_be_bytes(payload[8..16].try_into().unwrap());
+    (seq, tx_time_s)
+}
+
+/// Resolves `host` to a `SocketAddr`, accepting IPv4 and IPv6 literals
+/// directly and falling back to DNS resolution (picking whichever of
+/// IPv4/IPv6 the resolver returns first) for hostnames.
+async fn resolve_addr(host: &str, port: u16) -> Result<SocketAddr> {
+    if let Ok(ip) = IpAddr::from_str(host) {
+        return Ok(SocketAddr::new(ip, port));
+    }
+
+    tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("failed to resolve address '{}'", host))?
+        .next()
+        .ok_or_else(|| anyhow!("address '{}' resolved to no addresses", host))
+}
+
+/// Widens `send_interval_us` if needed so the resulting packet rate doesn't
+/// push the link past `rate_limit_mbit_s`, letting a caller saturate a link
+/// to a precise bandwidth instead of a precise packet interval.
+fn rate_capped_send_interval_us(
+    send_interval_us: i32,
+    packet_size: i32,
+    rate_limit_mbit_s: Option<f64>,
+) -> u64 {
+    let configured_interval_us = send_interval_us as u64;
+
+    let rate_limit_mbit_s = match rate_limit_mbit_s {
+        Some(rate_limit_mbit_s) if rate_limit_mbit_s > 0.0 => rate_limit_mbit_s,
+        _ => return configured_interval_us,
+    };
+
+    let packet_bits = packet_size as f64 * 8.0;
+    let min_interval_us = (packet_bits / (rate_limit_mbit_s * 1_000_000.0) * 1_000_000.0).ceil() as u64;
+
+    configured_interval_us.max(min_interval_us)
+}
+
+/// How many sequence numbers behind the low watermark `ReorderWindow` still
+/// remembers as "evicted unfilled" so a very late arrival can be told apart
+/// from a genuine duplicate; bounds the memory `recently_lost` can use.
+const LATE_ARRIVAL_HORIZON: u64 = REORDER_WINDOW_SIZE as u64 * 4;
+
+/// Tracks which sequence numbers have been seen over a sliding window so Rx
+/// can tell loss apart from reordering, the same "batch out-of-order until
+/// contiguous" approach used by Solana's window service: a fixed-size
+/// `VecDeque` of slots ending at the highest sequence number seen so far; the
+/// front only advances when a sequence number past the window's end arrives,
+/// and slots that fall off the front unfilled are counted as lost.
+#[derive(Debug)]
+struct ReorderWindow {
+    window: VecDeque<bool>,
+    low_watermark: u64,
+    window_size: usize,
+    /// Sequence numbers that aged out of the window unfilled, kept around
+    /// for `LATE_ARRIVAL_HORIZON` more sequence numbers so a subsequent
+    /// arrival of one of them is reported as a late reorder rather than a
+    /// duplicate.
+    recently_lost: HashSet<u64>,
+}
+
+impl ReorderWindow {
+    fn new(window_size: usize) -> ReorderWindow {
+        ReorderWindow {
+            window: VecDeque::with_capacity(window_size),
+            low_watermark: 0,
+            window_size,
+            recently_lost: HashSet::new(),
+        }
+    }
+
+    /// Records `seq` as received and returns `(lost, reordered, duplicate)`:
+    /// `lost` is the number of sequence numbers that aged out of the window
+    /// unfilled as a result of this packet; `reordered` is set when `seq`
+    /// arrived out of order, either filling a gap behind other
+    /// already-received sequence numbers still in the window, or arriving
+    /// behind the low watermark for a sequence number that was counted lost
+    /// but never actually delivered; `duplicate` is set when `seq` was
+    /// already recorded as received, either still in the window or behind
+    /// the low watermark as a genuine re-delivery.
+    fn record(&mut self, seq: u64) -> (u64, bool, bool) {
+        if self.window.is_empty() {
+            self.low_watermark = seq;
+            self.window.push_back(true);
+            return (0, false, false);
+        }
+
+        if seq < self.low_watermark {
+            return if self.recently_lost.remove(&seq) {
+                (0, true, false)
+            } else {
+                (0, false, true)
+            };
+        }
+
+        let offset = (seq - self.low_watermark) as usize;
+
+        if offset >= self.window_size {
+            let advance = offset - self.window_size + 1;
+            let mut lost = 0u64;
+            for i in 0..advance {
+                match self.window.pop_front() {
+                    Some(true) => {}
+                    Some(false) => {
+                        lost += 1;
+                        self.recently_lost.insert(self.low_watermark + i as u64);
+                    }
+                    None => {
+                        lost += 1;
+                        self.recently_lost.insert(self.low_watermark + i as u64);
+                    }
+                }
+            }
+            self.low_watermark += advance as u64;
+            while self.window.len() < self.window_size - 1 {
+                self.window.push_back(false);
+            }
+            self.window.push_back(true);
+            let prune_before = self.low_watermark.saturating_sub(LATE_ARRIVAL_HORIZON);
+            self.recently_lost.retain(|&s| s >= prune_before);
+            (lost, false, false)
+        } else {
+            while self.window.len() <= offset {
+                self.window.push_back(false);
+            }
+            let already_received = self.window[offset];
+            let reordered = !already_received && offset != self.window.len() - 1;
+            self.window[offset] = true;
+            (0, reordered, already_received)
+        }
+    }
+}
+
+/// Ring buffer of recently sent Arq data packets, keyed by sequence number,
+/// so a NACK can be answered with a retransmit as long as the packet hasn't
+/// aged out yet.
+#[derive(Debug)]
+struct ArqSendBuffer {
+    packets: HashMap<u64, (Vec<u8>, u32)>,
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl ArqSendBuffer {
+    fn new(capacity: usize) -> ArqSendBuffer {
+        ArqSendBuffer {
+            packets: HashMap::new(),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn insert(&mut self, seq: u64, packet: Vec<u8>) {
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.packets.remove(&oldest);
+            }
+        }
+        self.order.push_back(seq);
+        self.packets.insert(seq, (packet, 0));
+    }
+
+    /// Returns the buffered packet for `seq` with its retransmit count bumped
+    /// and re-stamped into the packet header, or `None` if it already aged
+    /// out of the buffer.
+    fn prepare_retransmit(&mut self, seq: u64) -> Option<(Vec<u8>, u32)> {
+        let (packet, retransmit_count) = self.packets.get_mut(&seq)?;
+        *retransmit_count += 1;
+        packet[17..21].copy_from_slice(&retransmit_count.to_be_bytes());
+        Some((packet.clone(), *retransmit_count))
+    }
+}
+
+/// Tracks the contiguous receive window of an Arq receiver: sequence numbers
+/// below `next_expected` have already been delivered or were given up on
+/// when a gap grew past `ARQ_RECEIVE_WINDOW_SIZE`, and `ahead` holds
+/// numbers that arrived early while a gap in front of them is outstanding.
+#[derive(Debug, Default)]
+struct ArqReceiveWindow {
+    initialized: bool,
+    next_expected: u64,
+    ahead: HashSet<u64>,
+    /// Sequence numbers already NACKed for the gap currently open, so a run
+    /// of out-of-order arrivals behind the same gap doesn't re-request them
+    /// and trigger a NACK/retransmit storm.
+    nacked: HashSet<u64>,
+}
+
+impl ArqReceiveWindow {
+    /// Records `seq` as received and returns the sequence numbers that
+    /// should be NACKed as a result (empty if nothing is newly missing).
+    fn record(&mut self, seq: u64) -> Vec<u64> {
+        if !self.initialized {
+            self.initialized = true;
+            self.next_expected = seq + 1;
+            return Vec::new();
+        }
+
+        if seq < self.next_expected {
+            return Vec::new();
+        }
+
+        if seq == self.next_expected {
+            self.next_expected += 1;
+            while self.ahead.remove(&self.next_expected) {
+                self.next_expected += 1;
+            }
+            self.nacked.retain(|&s| s >= self.next_expected);
+            return Vec::new();
+        }
+
+        let missing: Vec<u64> = (self.next_expected..seq)
+            .filter(|s| !self.ahead.contains(s) && !self.nacked.contains(s))
+            .collect();
+        self.ahead.insert(seq);
+        self.nacked.extend(missing.iter().copied());
+
+        if seq - self.next_expected > ARQ_RECEIVE_WINDOW_SIZE {
+            // The gap grew past what we're willing to track; give up on it
+            // and slide the window up to this packet.
+            self.ahead.retain(|&s| s > seq - ARQ_RECEIVE_WINDOW_SIZE);
+            self.nacked.retain(|&s| s > seq - ARQ_RECEIVE_WINDOW_SIZE);
+            self.next_expected = seq - ARQ_RECEIVE_WINDOW_SIZE;
+        }
+
+        missing
+    }
+}
+
+fn encode_arq_data(seq: u64, tx_time_s: f64, retransmit_count: u32, payload_size: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; payload_size];
+    packet[0] = ARQ_TAG_DATA;
+    packet[1..9].copy_from_slice(&seq.to_be_bytes());
+    packet[9..17].copy_from_slice(&tx_time_s.to_be_bytes());
+    packet[17..21].copy_from_slice(&retransmit_count.to_be_bytes());
+    packet
+}
+
+fn decode_arq_data(packet: &[u8]) -> (u64, f64, u32) {
+    let seq = u64::from_be_bytes(packet[1..9].try_into().unwrap());
+    let tx_time_s = f64::from_be_bytes(packet[9..17].try_into().unwrap());
+    let retransmit_count = u32::from_be_bytes(packet[17..21].try_into().unwrap());
+    (seq, tx_time_s, retransmit_count)
+}
+
+fn encode_arq_nack(missing: &[u64]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(1 + 4 + missing.len() * 8);
+    packet.push(ARQ_TAG_NACK);
+    packet.extend_from_slice(&(missing.len() as u32).to_be_bytes());
+    for seq in missing {
+        packet.extend_from_slice(&seq.to_be_bytes());
+    }
+    packet
+}
+
+/// Returns `None` if `packet` is too short to hold the `count` field, or too
+/// short to hold the `count` sequence numbers it claims to carry.
+fn decode_arq_nack(packet: &[u8]) -> Option<Vec<u64>> {
+    if packet.len() < 5 {
+        return None;
+    }
+
+    let count = u32::from_be_bytes(packet[1..5].try_into().unwrap()) as usize;
+    if packet.len() < 5 + count * 8 {
+        return None;
+    }
+
+    let mut missing = Vec::with_capacity(count);
+    for i in 0..count {
+        let start = 5 + i * 8;
+        missing.push(u64::from_be_bytes(packet[start..start + 8].try_into().unwrap()));
+    }
+    Some(missing)
 }
 
 impl Link {
-    pub fn new(
+    /// Binds the link's socket and spawns its send/receive loop onto the
+    /// current tokio runtime. Many `Link`s can share one runtime instead of
+    /// each paying for a dedicated OS thread.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
         link_mode: LinkMode,
-        address: &str,
+        bind_address: &str,
         bind_port: u16,
+        target_address: &str,
         target_port: u16,
         packet_size: i32,
         tx: mpsc::Sender<LinkPacketData>,
         send_interval_us: i32,
+        rate_limit_mbit_s: Option<f64>,
+        fragmentation: Option<FragmentationConfig>,
     ) -> Result<Link> {
+        // Arq ignores `fragmentation` entirely (see the dispatch below) and
+        // always frames its own header via `encode_arq_data`, so its
+        // `packet_size` needs validating regardless of whether a
+        // (to-be-ignored) fragmentation config was also passed in.
+        if link_mode == LinkMode::Arq {
+            if (packet_size as usize) < ARQ_DATA_HEADER_LEN {
+                bail!(
+                    "packet_size must be at least {} bytes to hold the header",
+                    ARQ_DATA_HEADER_LEN
+                );
+            }
+        } else if let Some(fragmentation) = fragmentation {
+            if (fragmentation.mtu as usize) <= fragment::FRAGMENT_HEADER_LEN {
+                bail!(
+                    "mtu must be greater than {} bytes to hold the fragment header",
+                    fragment::FRAGMENT_HEADER_LEN
+                );
+            }
+        } else if (packet_size as usize) < HEADER_LEN {
+            bail!(
+                "packet_size must be at least {} bytes to hold the header",
+                HEADER_LEN
+            );
+        }
+
         let run = Arc::new(AtomicBool::new(true));
-        let run_thread = run.clone();
+        let run_task = run.clone();
 
-        let bind_addr = SocketAddr::new(IpAddr::from_str(&address).expect("error"), bind_port);
-        let target_addr = SocketAddr::new(IpAddr::from_str(&address).expect("error"), target_port);
+        let bind_addr = resolve_addr(bind_address, bind_port).await?;
+        let target_addr = resolve_addr(target_address, target_port).await?;
 
-        let sock = UdpSocket::bind(bind_addr)?;
-        sock.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let sock = UdpSocket::bind(bind_addr).await?;
 
-        let mut payload: Vec<u8> = Vec::with_capacity(9048);
-        match link_mode {
-            LinkMode::Tx => {
-                payload.resize_with(packet_size as usize, Default::default);
-            }
-            LinkMode::Rx => {
-                payload.resize_with(9048, Default::default);
+        let effective_send_interval_us =
+            rate_capped_send_interval_us(send_interval_us, packet_size, rate_limit_mbit_s);
+
+        let task = tokio::spawn(async move {
+            match (link_mode, fragmentation) {
+                (LinkMode::Tx, Some(config)) => {
+                    run_fragmented_tx_loop(sock, run_task, tx, target_addr, config, effective_send_interval_us)
+                        .await
+                }
+                (LinkMode::Tx, None) => {
+                    run_tx_loop(sock, run_task, tx, target_addr, packet_size, effective_send_interval_us)
+                        .await
+                }
+                (LinkMode::Rx, Some(config)) => {
+                    run_fragmented_rx_loop(sock, run_task, tx, config).await
+                }
+                (LinkMode::Rx, None) => run_rx_loop(sock, run_task, tx).await,
+                (LinkMode::Arq, _) => {
+                    run_arq_loop(
+                        sock,
+                        run_task,
+                        tx,
+                        target_addr,
+                        packet_size,
+                        effective_send_interval_us,
+                    )
+                    .await
+                }
             }
-        }
+        });
+
+        Ok(Link {
+            run,
+            task: Some(task),
+            link_mode,
+            bind_address: bind_address.to_owned(),
+            bind_port,
+            target_address: target_address.to_owned(),
+            target_port,
+            packet_size,
+            send_interval_us,
+        })
+    }
+}
+
+async fn run_tx_loop(
+    sock: UdpSocket,
+    run_task: Arc<AtomicBool>,
+    tx: mpsc::Sender<LinkPacketData>,
+    target_addr: SocketAddr,
+    packet_size: i32,
+    send_interval_us: u64,
+) {
+    let begin = Instant::now();
+
+    let mut payload: Vec<u8> = Vec::with_capacity(9048);
+    payload.resize_with(packet_size as usize, Default::default);
+
+    let mut next_tx_time_us: u64 = send_interval_us;
+    let mut next_seq: u64 = 0;
+
+    while run_task.load(Ordering::SeqCst) {
+        let deadline = begin + Duration::from_micros(next_tx_time_us);
+        sleep_until(deadline).await;
+        next_tx_time_us += send_interval_us;
 
-        let thread = thread::spawn(move || {
-            let begin = Instant::now();
+        let tx_time = SystemTime::now();
+        let since_the_epoch = tx_time
+            .duration_since(UNIX_EPOCH)
+            .expect("error converting time");
 
-            let mut next_tx_time_us = send_interval_us;
+        let seq = next_seq;
+        next_seq += 1;
+        write_header(&mut payload, seq, since_the_epoch.as_secs_f64());
 
-            while run_thread.load(Ordering::SeqCst) {
-                match link_mode {
-                    LinkMode::Tx => {
-                        if Instant::now().saturating_duration_since(begin)
-                            > Duration::from_micros(next_tx_time_us as u64)
-                        {
+        sock.send_to(&payload, target_addr).await.ok();
+
+        tx.send(LinkPacketData {
+            t: since_the_epoch.as_secs_f64(),
+            payload_size: packet_size,
+            seq,
+            latency_s: 0.0,
+            lost_count: 0,
+            reordered: false,
+            duplicate: false,
+            retransmit_count: 0,
+            fragment_count: 1,
+            aborted: false,
+        })
+        .await
+        .ok();
+    }
+}
+
+async fn run_rx_loop(sock: UdpSocket, run_task: Arc<AtomicBool>, tx: mpsc::Sender<LinkPacketData>) {
+    let mut payload: Vec<u8> = Vec::with_capacity(9048);
+    payload.resize_with(9048, Default::default);
+
+    let mut reorder_window = ReorderWindow::new(REORDER_WINDOW_SIZE);
+
+    while run_task.load(Ordering::SeqCst) {
+        tokio::select! {
+            result = sock.recv_from(payload.as_mut_slice()) => {
+                match result {
+                    Ok((received, _from)) => {
+                        let rx_time = SystemTime::now();
+                        let since_the_epoch = rx_time
+                            .duration_since(UNIX_EPOCH)
+                            .expect("error converting time");
+
+                        if received >= HEADER_LEN {
+                            let (seq, tx_time_s) = read_header(&payload);
+                            let (lost, reordered, duplicate) = reorder_window.record(seq);
+
+                            tx.send(LinkPacketData {
+                                t: since_the_epoch.as_secs_f64(),
+                                payload_size: received as i32,
+                                seq,
+                                latency_s: since_the_epoch.as_secs_f64() - tx_time_s,
+                                lost_count: lost,
+                                reordered,
+                                duplicate,
+                                retransmit_count: 0,
+                                fragment_count: 1,
+                                aborted: false,
+                            })
+                            .await
+                            .ok();
+                        } else {
                             println!(
-                                "Socket send took too much time? ({} > {})",
-                                Instant::now().saturating_duration_since(begin).as_micros()
-                                    - Duration::from_micros(next_tx_time_us as u64).as_micros(),
-                                next_tx_time_us
+                                "Received packet too small to hold header ({} < {})",
+                                received, HEADER_LEN
                             );
                         }
+                    }
+                    Err(e) => println!("sock recv_from failed: {:?}", e),
+                }
+            }
+            // Re-check run_task periodically so Stop is noticed even while
+            // no packets are arriving.
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+        }
+    }
+}
 
-                        while Instant::now().saturating_duration_since(begin)
-                            < Duration::from_micros(next_tx_time_us as u64)
-                            && run_thread.load(Ordering::SeqCst)
-                        {}
+async fn run_arq_loop(
+    sock: UdpSocket,
+    run_task: Arc<AtomicBool>,
+    tx: mpsc::Sender<LinkPacketData>,
+    target_addr: SocketAddr,
+    packet_size: i32,
+    send_interval_us: u64,
+) {
+    let begin = Instant::now();
 
-                        let tx_time = SystemTime::now();
-                        let since_the_epoch = tx_time
+    let mut recv_buf: Vec<u8> = vec![0u8; 9048];
+
+    let mut next_tx_time_us: u64 = send_interval_us;
+    let mut next_seq: u64 = 0;
+    let mut send_buffer = ArqSendBuffer::new(ARQ_SEND_BUFFER_CAPACITY);
+    let mut receive_window = ArqReceiveWindow::default();
+
+    while run_task.load(Ordering::SeqCst) {
+        let deadline = begin + Duration::from_micros(next_tx_time_us);
+
+        tokio::select! {
+            _ = sleep_until(deadline) => {
+                next_tx_time_us += send_interval_us;
+
+                let tx_time = SystemTime::now();
+                let since_the_epoch = tx_time
+                    .duration_since(UNIX_EPOCH)
+                    .expect("error converting time");
+
+                let seq = next_seq;
+                next_seq += 1;
+                let packet = encode_arq_data(seq, since_the_epoch.as_secs_f64(), 0, packet_size as usize);
+
+                sock.send_to(&packet, target_addr).await.ok();
+                send_buffer.insert(seq, packet);
+
+                tx.send(LinkPacketData {
+                    t: since_the_epoch.as_secs_f64(),
+                    payload_size: packet_size,
+                    seq,
+                    latency_s: 0.0,
+                    lost_count: 0,
+                    reordered: false,
+                    duplicate: false,
+                    retransmit_count: 0,
+                    fragment_count: 1,
+                    aborted: false,
+                })
+                .await
+                .ok();
+            }
+            result = sock.recv_from(recv_buf.as_mut_slice()) => {
+                match result {
+                    Ok((received, from)) if received > 0 && recv_buf[0] == ARQ_TAG_NACK => {
+                        let missing = match decode_arq_nack(&recv_buf[..received]) {
+                            Some(missing) => missing,
+                            None => {
+                                println!("Received malformed Arq NACK ({} bytes)", received);
+                                continue;
+                            }
+                        };
+                        for seq in missing {
+                            if let Some((packet, retransmit_count)) = send_buffer.prepare_retransmit(seq) {
+                                sock.send_to(&packet, from).await.ok();
+
+                                tx.send(LinkPacketData {
+                                    t: SystemTime::now()
+                                        .duration_since(UNIX_EPOCH)
+                                        .expect("error converting time")
+                                        .as_secs_f64(),
+                                    payload_size: packet.len() as i32,
+                                    seq,
+                                    latency_s: 0.0,
+                                    lost_count: 0,
+                                    reordered: false,
+                                    duplicate: false,
+                                    retransmit_count,
+                                    fragment_count: 1,
+                                    aborted: false,
+                                })
+                                .await
+                                .ok();
+                            }
+                        }
+                    }
+                    Ok((received, from)) if received >= ARQ_DATA_HEADER_LEN => {
+                        let rx_time = SystemTime::now();
+                        let since_the_epoch = rx_time
                             .duration_since(UNIX_EPOCH)
                             .expect("error converting time");
 
-                        next_tx_time_us += send_interval_us;
+                        let (seq, tx_time_s, retransmit_count) = decode_arq_data(&recv_buf[..received]);
+                        let missing = receive_window.record(seq);
 
-                        sock.send_to(&payload, target_addr).ok();
+                        if !missing.is_empty() {
+                            let nack = encode_arq_nack(&missing);
+                            sock.send_to(&nack, from).await.ok();
+                        }
 
                         tx.send(LinkPacketData {
                             t: since_the_epoch.as_secs_f64(),
-                            payload_size: packet_size,
+                            payload_size: received as i32,
+                            seq,
+                            latency_s: since_the_epoch.as_secs_f64() - tx_time_s,
+                            lost_count: 0,
+                            reordered: false,
+                            duplicate: false,
+                            retransmit_count,
+                            fragment_count: 1,
+                            aborted: false,
                         })
-                        .expect("error sending data on channel");
+                        .await
+                        .ok();
+                    }
+                    Ok((received, _from)) => {
+                        println!("Received undersized Arq packet ({} bytes)", received);
                     }
-                    LinkMode::Rx => match sock.recv_from(payload.as_mut_slice()) {
-                        Ok((received, _from)) => {
-                            let rx_time = SystemTime::now();
-                            let since_the_epoch = rx_time
+                    Err(e) => println!("sock recv_from failed: {:?}", e),
+                }
+            }
+        }
+    }
+}
+
+async fn run_fragmented_tx_loop(
+    sock: UdpSocket,
+    run_task: Arc<AtomicBool>,
+    tx: mpsc::Sender<LinkPacketData>,
+    target_addr: SocketAddr,
+    config: FragmentationConfig,
+    send_interval_us: u64,
+) {
+    let begin = Instant::now();
+
+    let message: Vec<u8> = vec![0u8; config.message_size as usize];
+
+    let mut next_tx_time_us: u64 = send_interval_us;
+    let mut next_message_id: u64 = 0;
+
+    while run_task.load(Ordering::SeqCst) {
+        let deadline = begin + Duration::from_micros(next_tx_time_us);
+        sleep_until(deadline).await;
+        next_tx_time_us += send_interval_us;
+
+        let message_id = next_message_id;
+        next_message_id += 1;
+
+        let fragments = fragment::fragment_message(message_id, &message, config.mtu as usize);
+        let fragment_count = fragments.len() as u32;
+        for datagram in &fragments {
+            sock.send_to(datagram, target_addr).await.ok();
+        }
+
+        tx.send(LinkPacketData {
+            t: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("error converting time")
+                .as_secs_f64(),
+            payload_size: config.message_size,
+            seq: message_id,
+            latency_s: 0.0,
+            lost_count: 0,
+            reordered: false,
+            duplicate: false,
+            retransmit_count: 0,
+            fragment_count,
+            aborted: false,
+        })
+        .await
+        .ok();
+    }
+}
+
+async fn run_fragmented_rx_loop(
+    sock: UdpSocket,
+    run_task: Arc<AtomicBool>,
+    tx: mpsc::Sender<LinkPacketData>,
+    config: FragmentationConfig,
+) {
+    let mut recv_buf: Vec<u8> = vec![0u8; config.mtu as usize];
+    let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT);
+    let mut expire_tick = tokio::time::interval(Duration::from_millis(500));
+
+    while run_task.load(Ordering::SeqCst) {
+        tokio::select! {
+            result = sock.recv_from(recv_buf.as_mut_slice()) => {
+                match result {
+                    Ok((received, _from)) => {
+                        let now = std::time::Instant::now();
+                        if let Some(completed) = reassembler.on_fragment(&recv_buf[..received], now) {
+                            let rx_time = SystemTime::now()
                                 .duration_since(UNIX_EPOCH)
                                 .expect("error converting time");
+
                             tx.send(LinkPacketData {
-                                t: since_the_epoch.as_secs_f64(),
-                                payload_size: received as i32,
+                                t: rx_time.as_secs_f64(),
+                                payload_size: completed.total_len as i32,
+                                seq: completed.message_id,
+                                latency_s: 0.0,
+                                lost_count: 0,
+                                reordered: false,
+                                duplicate: false,
+                                retransmit_count: 0,
+                                fragment_count: completed.fragment_count,
+                                aborted: false,
                             })
-                            .expect("error sending data on channel");
-                        }
-                        Err(ref e) if e.kind() != std::io::ErrorKind::TimedOut => {
-                            println!("sock recv_from failed: {:?}", e)
+                            .await
+                            .ok();
                         }
-                        Err(_) => (),
-                    },
+                    }
+                    Err(e) => println!("sock recv_from failed: {:?}", e),
                 }
             }
-        });
+            _ = expire_tick.tick() => {
+                let now = std::time::Instant::now();
+                for aborted in reassembler.expire(now) {
+                    println!(
+                        "Message {} aborted: {}/{} fragments received",
+                        aborted.message_id, aborted.fragments_received, aborted.fragment_count
+                    );
 
-        Ok(Link {
-            run: run,
-            thread: Some(thread),
-            link_mode: link_mode,
-            address: address.to_owned(),
-            bind_port: bind_port,
-            target_port: target_port,
-            packet_size: packet_size,
-            send_interval_us: send_interval_us,
-        })
+                    tx.send(LinkPacketData {
+                        t: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .expect("error converting time")
+                            .as_secs_f64(),
+                        payload_size: aborted.total_len as i32,
+                        seq: aborted.message_id,
+                        latency_s: 0.0,
+                        lost_count: 0,
+                        reordered: false,
+                        duplicate: false,
+                        retransmit_count: 0,
+                        fragment_count: aborted.fragment_count,
+                        aborted: true,
+                    })
+                    .await
+                    .ok();
+                }
+            }
+        }
     }
 }
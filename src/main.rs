@@ -1,6 +1,5 @@
 #![windows_subsystem = "windows"]
 
-use core::cmp;
 use glium::glutin;
 use glium::glutin::event::{Event, WindowEvent};
 use glium::glutin::event_loop::{ControlFlow, EventLoop};
@@ -10,13 +9,15 @@ use imgui::*;
 use imgui::{Context, FontConfig, FontGlyphRanges, FontSource, Ui};
 use imgui_glium_renderer::Renderer;
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use std::sync::atomic::Ordering;
 use std::time::Instant;
-use std::{path::Path, sync::Arc};
+use std::path::Path;
 
-mod rx;
-use rx::Rx;
-mod tx;
-use tx::Tx;
+mod fragment;
+mod link;
+mod stats;
+use link::{Link, LinkMode};
+use stats::LinkStatsCollector;
 
 struct System {
     pub event_loop: EventLoop<()>,
@@ -24,6 +25,9 @@ struct System {
     pub imgui: Context,
     pub platform: WinitPlatform,
     pub renderer: Renderer,
+    /// Kept on the struct for any UI code that wants to lay out text at the
+    /// loaded font's pixel size; not read internally.
+    #[allow(dead_code)]
     pub font_size: f32,
 }
 
@@ -83,6 +87,119 @@ impl System {
     }
 }
 
+/// Bundles a `Link` with the `LinkStatsCollector` reading its reported
+/// packets, so the UI has one `Start`/`Stop`-able handle for the pair. `Link`
+/// has no `Drop` impl of its own (its `run`/`task` fields are exposed so an
+/// owner can manage its lifetime directly), so this wrapper's `Drop` is what
+/// actually stops the background tokio task.
+#[derive(Debug)]
+struct LinkHandle {
+    link: Link,
+    stats: LinkStatsCollector,
+}
+
+impl Drop for LinkHandle {
+    fn drop(&mut self) {
+        self.link.run.store(false, Ordering::SeqCst);
+    }
+}
+
+impl LinkHandle {
+    /// `false` once the link's background task has stopped, e.g. because it
+    /// hit an unrecoverable error.
+    fn is_running(&self) -> bool {
+        self.link
+            .task
+            .as_ref()
+            .map(|task| !task.is_finished())
+            .unwrap_or(false)
+    }
+}
+
+/// A `Link::new` call in flight: DNS resolution and binding happen on the
+/// tokio runtime instead of inline on the UI thread, since a slow or failing
+/// hostname lookup would otherwise freeze the whole event loop for the
+/// resolution/timeout duration. Polled once per frame from the UI loop until
+/// `task` finishes.
+#[derive(Debug)]
+struct PendingLink {
+    task: tokio::task::JoinHandle<anyhow::Result<Link>>,
+    packet_rx: Option<tokio::sync::mpsc::Receiver<link::LinkPacketData>>,
+    send_interval_us: i32,
+    stats_window: std::time::Duration,
+}
+
+impl PendingLink {
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        runtime: &tokio::runtime::Runtime,
+        link_mode: LinkMode,
+        bind_address: &str,
+        target_address: &str,
+        target_port: u16,
+        packet_size: i32,
+        send_interval_us: i32,
+        rate_limit_mbit_s: Option<f64>,
+        fragmentation: Option<link::FragmentationConfig>,
+        stats_window: std::time::Duration,
+    ) -> PendingLink {
+        let (packet_tx, packet_rx) = tokio::sync::mpsc::channel(1024);
+
+        let bind_address = bind_address.to_owned();
+        let target_address = target_address.to_owned();
+        let task = runtime.spawn(async move {
+            Link::new(
+                link_mode,
+                &bind_address,
+                0,
+                &target_address,
+                target_port,
+                packet_size,
+                packet_tx,
+                send_interval_us,
+                rate_limit_mbit_s,
+                fragmentation,
+            )
+            .await
+        });
+
+        PendingLink {
+            task,
+            packet_rx: Some(packet_rx),
+            send_interval_us,
+            stats_window,
+        }
+    }
+
+    /// Resolves to the finished `LinkHandle` once `task` completes, `None`
+    /// while it's still resolving/binding.
+    fn poll(&mut self, runtime: &tokio::runtime::Runtime) -> Option<anyhow::Result<LinkHandle>> {
+        if !self.task.is_finished() {
+            return None;
+        }
+
+        Some(match runtime.block_on(&mut self.task) {
+            Ok(Ok(link)) => {
+                let packet_rx = self.packet_rx.take().expect("polled after completion");
+                // `LinkStatsCollector::new` spawns onto the current runtime.
+                let _runtime_guard = runtime.enter();
+                let stats = LinkStatsCollector::new(packet_rx, self.send_interval_us, self.stats_window);
+                Ok(LinkHandle { link, stats })
+            }
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(anyhow::anyhow!("link startup task panicked: {}", e)),
+        })
+    }
+}
+
+/// The `Link`/`Link`-panel's Start button leads to a `Pending` resolve/bind
+/// before it becomes a `Ready`, runnable `LinkHandle`.
+#[derive(Debug)]
+enum LinkSlot {
+    Pending(PendingLink),
+    Ready(LinkHandle),
+}
+
 fn init(title: &str) -> System {
     let title = match Path::new(&title).file_name() {
         Some(file_name) => file_name.to_str().unwrap(),
@@ -92,7 +209,7 @@ fn init(title: &str) -> System {
     let context = glutin::ContextBuilder::new().with_vsync(true);
     let builder = WindowBuilder::new()
         .with_title(title.to_owned())
-        .with_inner_size(glutin::dpi::LogicalSize::new(1000f64, 200f64));
+        .with_inner_size(glutin::dpi::LogicalSize::new(500f64, 400f64));
     let display =
         Display::new(builder, context, &event_loop).expect("Failed to initialize display");
 
@@ -146,36 +263,26 @@ fn init(title: &str) -> System {
 fn main() {
     let system = init("Voysys UDP Test");
 
-    let mut rx_im_string_bind_ip = ImString::new("0.0.0.0");
-    rx_im_string_bind_ip.reserve(128);
-
-    let mut rx_listen_port = 27000;
-
-    let mut rx: Arc<Option<Rx>> =
-        Arc::new(Rx::new(rx_im_string_bind_ip.as_ref(), rx_listen_port).ok());
+    let tokio_runtime = tokio::runtime::Runtime::new().expect("Failed to start tokio runtime");
 
-    let mut tx_target_port = 27000;
-    let mut tx_packet_size = 500;
-    let mut tx_send_interval_us = 10000;
+    let mut link_mode = LinkMode::Arq;
+    let mut link_target_port = 27001;
+    let mut link_packet_size = 500;
+    let mut link_send_interval_us = 10000;
+    let mut link_fragmented = false;
+    let mut link_fragment_mtu = link::DEFAULT_FRAGMENT_MTU;
+    let mut link_message_size = link::DEFAULT_FRAGMENT_MTU * 4;
+    let mut link_rate_limited = false;
+    let mut link_rate_limit_mbit_s: f32 = 10.0;
+    let mut link_stats_window_s: f32 = 5.0;
 
-    let mut tx_im_string_target_ip = ImString::new("127.0.0.1");
-    tx_im_string_target_ip.reserve(128);
+    let mut link_im_string_target_ip = ImString::new("127.0.0.1");
+    link_im_string_target_ip.reserve(128);
 
-    let mut tx_im_string_bind_ip = ImString::new("0.0.0.0");
-    tx_im_string_bind_ip.reserve(128);
+    let mut link_im_string_bind_ip = ImString::new("0.0.0.0");
+    link_im_string_bind_ip.reserve(128);
 
-    let mut tx: Arc<Option<Tx>> = Arc::new(
-        Tx::new(
-            tx_im_string_bind_ip.as_ref(),
-            tx_im_string_target_ip.as_ref(),
-            tx_target_port,
-            tx_packet_size,
-            tx_send_interval_us,
-        )
-        .ok(),
-    );
-
-    let mut stat_length_s: f32 = 5.0;
+    let mut link: Option<LinkSlot> = None;
 
     system.main_loop(move |_, ui| {
         let view_size = ui.io().display_size;
@@ -209,8 +316,8 @@ fn main() {
             (StyleColor::FrameBgHovered, [0.3, 0.2, 0.09, 1.0]),
         ]);
 
-        Window::new(im_str!("UDP Test: Tx"))
-            .size([400.0, view_size[1]], Condition::Always)
+        Window::new(im_str!("UDP Test: Link"))
+            .size(view_size, Condition::Always)
             .position([0.0, 0.0], Condition::Always)
             .movable(false)
             .resizable(false)
@@ -219,219 +326,178 @@ fn main() {
             .menu_bar(false)
             .focused(false)
             .build(ui, || {
-                ui.text(im_str!("UDP Test: Tx"));
+                ui.text(im_str!("UDP Test: Link"));
                 ui.separator();
 
                 let mut stop = false;
                 let mut start = false;
 
-                match tx.as_ref() {
-                    Some(tx) => {
+                if let Some(LinkSlot::Pending(pending)) = &mut link {
+                    if let Some(resolved) = pending.poll(&tokio_runtime) {
+                        link = match resolved {
+                            Ok(link_handle) => Some(LinkSlot::Ready(link_handle)),
+                            Err(e) => {
+                                println!("Failed to start link: {:?}", e);
+                                None
+                            }
+                        };
+                    }
+                }
+
+                match &link {
+                    Some(LinkSlot::Pending(_)) => {
+                        if ui.small_button(im_str!("Cancel")) {
+                            stop = true;
+                        }
+                        ui.text(im_str!("Resolving/binding..."));
+                    }
+                    Some(LinkSlot::Ready(link_handle)) => {
                         if ui.small_button(im_str!("Stop")) {
                             stop = true;
                         }
 
+                        let status = if link_handle.is_running() {
+                            "running"
+                        } else {
+                            "terminated unexpectedly"
+                        };
+                        ui.text(format!("Mode: {:?} ({})", link_handle.link.link_mode, status));
+                        ui.text(format!(
+                            "Bind: {}:{}",
+                            link_handle.link.bind_address, link_handle.link.bind_port
+                        ));
                         ui.text(format!(
-                            "Bind IP: {}",
-                            tx_im_string_bind_ip.as_ref() as &str
+                            "Target: {}:{}",
+                            link_handle.link.target_address, link_handle.link.target_port
                         ));
+                        ui.text(format!("Packet Size: {}", link_handle.link.packet_size));
                         ui.text(format!(
-                            "Destination IP: {}",
-                            tx_im_string_target_ip.as_ref() as &str
+                            "Send Interval: {} µs",
+                            link_handle.link.send_interval_us
                         ));
-                        ui.text(format!("Destination Port: {}", tx_target_port));
-                        ui.text(format!("Packet Size: {}", tx_packet_size));
-                        ui.text(format!("Send Interval: {} µs", tx_send_interval_us));
 
-                        ui.text(format!("Sent packets: {}", tx.get_send_count()));
+                        let summary = link_handle.stats.get_summary();
+                        ui.text(format!(
+                            "Goodput: {:.02} Mbit/s (+{:.02} Mbit/s retransmitted)",
+                            summary.throughput_mbit_s, summary.retransmit_throughput_mbit_s
+                        ));
+                        ui.text(format!(
+                            "Packet rate: {:.01} Hz ({} packets in window)",
+                            summary.packet_rate_hz, summary.packet_count
+                        ));
+                        ui.text(format!(
+                            "Jitter: {:.02} ms, Mean latency: {:.02} ms",
+                            1000.0 * summary.jitter_s,
+                            1000.0 * summary.mean_latency_s
+                        ));
+                        ui.text(format!(
+                            "Lost: {}, Reordered: {}, Duplicate: {}, Retransmits: {}, Aborted: {}",
+                            summary.lost_count,
+                            summary.reordered_count,
+                            summary.duplicate_count,
+                            summary.retransmit_count,
+                            summary.aborted_count
+                        ));
+                        ui.text(format!(
+                            "Fragmented packets: {}, Latest seq: {}",
+                            summary.fragmented_packet_count, summary.latest_seq
+                        ));
                     }
                     None => {
                         if ui.small_button(im_str!("Start")) {
                             start = true;
                         }
-                        ui.input_text(im_str!("Bind IP"), &mut tx_im_string_bind_ip)
+
+                        ui.radio_button(im_str!("Tx"), &mut link_mode, LinkMode::Tx);
+                        ui.same_line(0.0);
+                        ui.radio_button(im_str!("Rx"), &mut link_mode, LinkMode::Rx);
+                        ui.same_line(0.0);
+                        ui.radio_button(im_str!("Arq"), &mut link_mode, LinkMode::Arq);
+
+                        ui.input_text(im_str!("Bind IP"), &mut link_im_string_bind_ip)
                             .build();
-                        ui.input_text(im_str!("Destination IP"), &mut tx_im_string_target_ip)
+                        ui.input_text(im_str!("Target IP"), &mut link_im_string_target_ip)
                             .build();
-                        Drag::new(im_str!("Destination Port"))
+                        Drag::new(im_str!("Target Port"))
                             .range(1..=65236)
-                            .build(ui, &mut tx_target_port);
+                            .build(ui, &mut link_target_port);
                         Drag::new(im_str!("Packet Size"))
                             .range(64..=1400)
-                            .build(ui, &mut tx_packet_size);
+                            .build(ui, &mut link_packet_size);
                         Drag::new(im_str!("Send Interval"))
                             .range(500..=1000000)
                             .display_format(im_str!("%d µs"))
-                            .build(ui, &mut tx_send_interval_us);
-                    }
-                }
-
-                if stop {
-                    tx = Arc::new(None);
-                }
-
-                if start {
-                    tx = Arc::new(
-                        Tx::new(
-                            tx_im_string_bind_ip.as_ref(),
-                            tx_im_string_target_ip.as_ref(),
-                            tx_target_port,
-                            tx_packet_size,
-                            tx_send_interval_us,
-                        )
-                        .ok(),
-                    );
-                }
-            });
-
-        let rx_window_width = if view_size[0] > 401.0 {
-            view_size[0] - 400.0
-        } else {
-            1.0
-        };
-
-        Window::new(im_str!("UDP Test: Rx"))
-            .size([rx_window_width, view_size[1]], Condition::Always)
-            .position([400.0, 0.0], Condition::Always)
-            .movable(false)
-            .resizable(false)
-            .title_bar(false)
-            .collapsible(false)
-            .menu_bar(false)
-            .focused(false)
-            .build(ui, || {
-                ui.text(im_str!("UDP Test: Rx"));
-                ui.separator();
-
-                let mut stop = false;
-                let mut start = false;
-
-                match rx.as_ref() {
-                    Some(rx) => {
-                        if ui.small_button(im_str!("Stop")) {
-                            stop = true;
-                        }
-
-                        ui.text(format!(
-                            "Bind IP: {}",
-                            rx_im_string_bind_ip.as_ref() as &str
-                        ));
-                        ui.text(format!("Listen Port: {}", rx_listen_port));
+                            .build(ui, &mut link_send_interval_us);
 
                         Drag::new(im_str!("Statistics Window Length"))
                             .range(0.1..=1000.0)
                             .display_format(im_str!("%.02f s"))
                             .speed(0.01)
-                            .build(ui, &mut stat_length_s);
-
-                        {
-                            let t_diff_data = rx.get_t_diff_data();
-                            let t_rx_data = rx.get_t_rx_data();
-
-                            if t_diff_data.len() > 2 && t_rx_data.len() > 2 {
-                                let last_time = t_rx_data.last().unwrap();
-                                let start_window_time = last_time - stat_length_s as f64;
-
-                                let mut first_sample = t_rx_data.len() - 2;
-                                while first_sample != 0
-                                    && t_rx_data[first_sample] > start_window_time
-                                {
-                                    first_sample -= 1;
-                                }
-
-                                let start_window_time = t_rx_data[first_sample];
-                                let end_window_time = t_rx_data.last().unwrap();
-                                let window_time = end_window_time - start_window_time;
-
-                                let sample_count = t_rx_data.len() - first_sample as usize;
-                                ui.text(format!(
-                                    "Rx packets in statistics range: {}",
-                                    sample_count as i64
-                                ));
-
-                                let t_rx_data = &t_rx_data[first_sample..];
-                                let t_diff_data = &t_diff_data[first_sample..];
-
-                                ui.plot_lines(im_str!("Delta Times"), t_diff_data)
-                                    .scale_min(0.0)
-                                    .build();
-
-                                {
-                                    let mut average = 0.0;
-                                    let mut min = std::f32::MAX;
-                                    let mut max = std::f32::MIN;
-
-                                    for v in t_diff_data {
-                                        average += v;
-                                        if v < &min {
-                                            min = *v;
-                                        }
-                                        if v > &max {
-                                            max = *v;
-                                        }
-                                    }
-
-                                    average = average / t_diff_data.len() as f32;
-
-                                    ui.text(format!(
-                                        "Min: {:.02}, Max: {:.02}, Average: {:.02} (ms)",
-                                        1000.0 * min,
-                                        1000.0 * max,
-                                        1000.0 * average
-                                    ));
-                                }
-
-                                let time_samples = cmp::min(100, sample_count / 10);
-                                let time_samples_dt = window_time / time_samples as f64;
-                                let mut sample_start_i: usize = 0;
-                                let mut sample_end_i: usize = 0;
-                                let mut time_sample_time_i = start_window_time;
-                                let mut time_samples_data = Vec::new();
-                                for _ in 0..time_samples {
-                                    sample_start_i = sample_end_i;
-                                    time_sample_time_i += time_samples_dt;
-                                    while sample_end_i < sample_count
-                                        && t_rx_data[sample_end_i] < time_sample_time_i
-                                    {
-                                        sample_end_i += 1;
-                                    }
-                                    time_samples_data.push((sample_end_i - sample_start_i) as f32);
-                                }
-                                ui.plot_lines(
-                                    im_str!("Packets Per Time"),
-                                    time_samples_data.as_slice(),
-                                )
-                                .scale_min(0.0)
-                                .build();
-                            }
+                            .build(ui, &mut link_stats_window_s);
+
+                        ui.checkbox(im_str!("Cap send rate"), &mut link_rate_limited);
+                        if link_rate_limited {
+                            Drag::new(im_str!("Rate Limit"))
+                                .range(0.1..=10000.0)
+                                .display_format(im_str!("%.01f Mbit/s"))
+                                .speed(0.1)
+                                .build(ui, &mut link_rate_limit_mbit_s);
                         }
 
-                        ui.spacing();
-                    }
-                    None => {
-                        if ui.small_button(im_str!("Start")) {
-                            start = true;
+                        if link_mode == LinkMode::Arq {
+                            link_fragmented = false;
+                        } else {
+                            ui.checkbox(im_str!("Fragment large messages"), &mut link_fragmented);
+                            if link_fragmented {
+                                Drag::new(im_str!("Fragment MTU"))
+                                    .range(64..=9000)
+                                    .build(ui, &mut link_fragment_mtu);
+                                Drag::new(im_str!("Message Size"))
+                                    .range(link_fragment_mtu..=1_000_000)
+                                    .build(ui, &mut link_message_size);
+                            }
                         }
-                        ui.input_text(im_str!("Bind IP"), &mut rx_im_string_bind_ip)
-                            .build();
-                        Drag::new(im_str!("Listen Port"))
-                            .range(1..=65236)
-                            .build(ui, &mut rx_listen_port);
                     }
                 }
 
                 if stop {
-                    rx = Arc::new(None);
+                    if let Some(LinkSlot::Pending(pending)) = &link {
+                        pending.task.abort();
+                    }
+                    link = None;
                 }
 
                 if start {
-                    rx = Arc::new(Rx::new(tx_im_string_bind_ip.as_ref(), rx_listen_port).ok());
-                    if !rx.is_some() {
-                        println!("Failed to open");
-                    }
+                    let fragmentation = if link_fragmented {
+                        Some(link::FragmentationConfig {
+                            message_size: link_message_size,
+                            mtu: link_fragment_mtu,
+                        })
+                    } else {
+                        None
+                    };
+
+                    let rate_limit_mbit_s = if link_rate_limited {
+                        Some(link_rate_limit_mbit_s as f64)
+                    } else {
+                        None
+                    };
+
+                    link = Some(LinkSlot::Pending(PendingLink::spawn(
+                        &tokio_runtime,
+                        link_mode,
+                        link_im_string_bind_ip.as_ref(),
+                        link_im_string_target_ip.as_ref(),
+                        link_target_port,
+                        link_packet_size,
+                        link_send_interval_us,
+                        rate_limit_mbit_s,
+                        fragmentation,
+                        std::time::Duration::from_secs_f32(link_stats_window_s),
+                    )));
                 }
             });
 
-        style_colors.pop(&ui);
+        style_colors.pop(ui);
     });
 }
@@ -0,0 +1,198 @@
+use crate::link::LinkPacketData;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Rolling-window aggregate of a link's recent `LinkPacketData`, recomputed
+/// once per timer tick.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkStatsSummary {
+    /// Throughput of original (non-retransmitted) payload bytes, i.e. the
+    /// effective goodput.
+    pub throughput_mbit_s: f64,
+    /// Throughput of retransmitted payload bytes, counted separately so
+    /// retransmits don't inflate the goodput figure above.
+    pub retransmit_throughput_mbit_s: f64,
+    pub packet_rate_hz: f64,
+    /// Mean absolute deviation of inter-packet arrival time against the
+    /// nominal `send_interval_us`.
+    pub jitter_s: f64,
+    /// Mean one-way latency (send to receive) of packets in the window.
+    pub mean_latency_s: f64,
+    pub packet_count: u64,
+    pub lost_count: u64,
+    pub reordered_count: u64,
+    pub duplicate_count: u64,
+    pub retransmit_count: u64,
+    pub aborted_count: u64,
+    /// How many packets in the window belonged to a fragmented message
+    /// (`fragment_count > 1`).
+    pub fragmented_packet_count: u64,
+    /// Sequence number of the most recently received packet in the window.
+    pub latest_seq: u64,
+}
+
+/// Owns the receiving end of a link's reporting channel and continuously
+/// aggregates throughput, packet rate and jitter over a rolling time window.
+#[derive(Debug)]
+pub struct LinkStatsCollector {
+    run: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+    summary: Arc<Mutex<LinkStatsSummary>>,
+}
+
+impl Drop for LinkStatsCollector {
+    fn drop(&mut self) {
+        self.run.store(false, Ordering::SeqCst);
+        // The collector task can be parked in `packets.recv()` waiting on a
+        // sender that's also shutting down, so abort it directly rather than
+        // waiting for it to next notice `run` on a tick.
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+impl LinkStatsCollector {
+    /// `nominal_send_interval_us` is the link's configured `send_interval_us`,
+    /// used as the jitter baseline. `window` is how far back `packets` are
+    /// kept before they age out of the rolling aggregate.
+    pub fn new(
+        mut packets: mpsc::Receiver<LinkPacketData>,
+        nominal_send_interval_us: i32,
+        window: Duration,
+    ) -> LinkStatsCollector {
+        let run = Arc::new(AtomicBool::new(true));
+        let run_task = run.clone();
+
+        let summary = Arc::new(Mutex::new(LinkStatsSummary::default()));
+        let summary_task = summary.clone();
+
+        let task = tokio::spawn(async move {
+            let nominal_interval_s = nominal_send_interval_us as f64 / 1_000_000.0;
+            let window_s = window.as_secs_f64();
+            let mut window_packets: VecDeque<LinkPacketData> = VecDeque::new();
+            let mut tick = tokio::time::interval(Duration::from_secs(1));
+
+            while run_task.load(Ordering::SeqCst) {
+                tokio::select! {
+                    received = packets.recv() => {
+                        match received {
+                            Some(packet) => window_packets.push_back(packet),
+                            None => break,
+                        }
+                    }
+                    _ = tick.tick() => {
+                        if let Some(latest_t) = window_packets.back().map(|p| p.t) {
+                            let window_start = latest_t - window_s;
+                            while window_packets
+                                .front()
+                                .map(|p| p.t < window_start)
+                                .unwrap_or(false)
+                            {
+                                window_packets.pop_front();
+                            }
+                        }
+
+                        *summary_task.lock().unwrap() =
+                            summarize(&window_packets, nominal_interval_s, window_s);
+                    }
+                }
+            }
+        });
+
+        LinkStatsCollector {
+            run,
+            task: Some(task),
+            summary,
+        }
+    }
+
+    pub fn get_summary(&self) -> LinkStatsSummary {
+        *self.summary.lock().unwrap()
+    }
+}
+
+fn summarize(
+    packets: &VecDeque<LinkPacketData>,
+    nominal_interval_s: f64,
+    window_s: f64,
+) -> LinkStatsSummary {
+    if packets.is_empty() {
+        return LinkStatsSummary::default();
+    }
+
+    // Right after a link starts, the window hasn't filled up to `window_s`
+    // yet; dividing by the configured window length instead of the actual
+    // span covered by `packets` would understate throughput/packet rate by
+    // up to `window_s`. Fall back to `nominal_interval_s` rather than 0 when
+    // only one packet has arrived so far.
+    let elapsed_s = packets.back().unwrap().t - packets.front().unwrap().t;
+    let divisor_s = elapsed_s.min(window_s).max(nominal_interval_s);
+
+    let mut original_bits = 0.0;
+    let mut retransmit_bits = 0.0;
+    let mut latency_sum_s = 0.0;
+    let mut lost_count: u64 = 0;
+    let mut reordered_count: u64 = 0;
+    let mut duplicate_count: u64 = 0;
+    let mut retransmit_count: u64 = 0;
+    let mut aborted_count: u64 = 0;
+    let mut fragmented_packet_count: u64 = 0;
+    let mut latest_seq: u64 = 0;
+
+    for p in packets {
+        let bits = p.payload_size as f64 * 8.0;
+        if p.retransmit_count > 0 {
+            retransmit_bits += bits;
+        } else {
+            original_bits += bits;
+        }
+        latency_sum_s += p.latency_s;
+        lost_count += p.lost_count;
+        if p.reordered {
+            reordered_count += 1;
+        }
+        if p.duplicate {
+            duplicate_count += 1;
+        }
+        if p.aborted {
+            aborted_count += 1;
+        }
+        if p.fragment_count > 1 {
+            fragmented_packet_count += 1;
+        }
+        retransmit_count += p.retransmit_count as u64;
+        latest_seq = p.seq;
+    }
+
+    let mut jitter_sum_s = 0.0;
+    let mut jitter_sample_count: u64 = 0;
+    for (prev, next) in packets.iter().zip(packets.iter().skip(1)) {
+        jitter_sum_s += ((next.t - prev.t) - nominal_interval_s).abs();
+        jitter_sample_count += 1;
+    }
+
+    LinkStatsSummary {
+        throughput_mbit_s: original_bits / divisor_s / 1_000_000.0,
+        retransmit_throughput_mbit_s: retransmit_bits / divisor_s / 1_000_000.0,
+        packet_rate_hz: packets.len() as f64 / divisor_s,
+        jitter_s: if jitter_sample_count > 0 {
+            jitter_sum_s / jitter_sample_count as f64
+        } else {
+            0.0
+        },
+        mean_latency_s: latency_sum_s / packets.len() as f64,
+        packet_count: packets.len() as u64,
+        lost_count,
+        reordered_count,
+        duplicate_count,
+        retransmit_count,
+        aborted_count,
+        fragmented_packet_count,
+        latest_seq,
+    }
+}